@@ -1,14 +1,23 @@
 use core::str::Utf8Error;
+use std::cmp;
 use std::fs;
 use std::io;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::*;
 use std::ops::Deref;
+use std::str;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
 use iron::prelude::*;
-use iron::headers::CookiePair;
+use iron::headers::{AcceptRanges, ByteRangeSpec, ContentLength, ContentRange, ContentRangeSpec,
+                     ContentType, CookiePair, ETag, EntityTag, IfNoneMatch, Range, RangeUnit};
+use iron::typemap::Key;
 use iron::{BeforeMiddleware, status};
 use mount::Mount;
+use multipart::server::Multipart;
 use oven::prelude::*;
 use params;
 use rustc_serialize::json;
@@ -16,12 +25,25 @@ use url::percent_encoding::percent_decode;
 
 use collection::*;
 use error::*;
+use jwt::{self, Claims};
 use thumbnails::*;
 use utils::*;
 
 const CURRENT_MAJOR_VERSION: i32 = 1;
 const CURRENT_MINOR_VERSION: i32 = 0;
 
+const TOKEN_LIFETIME_SECONDS: i64 = 24 * 60 * 60;
+
+// Files larger than this are fingerprinted from size + mtime rather than by
+// hashing their full contents, so a multi-hundred-megabyte FLAC doesn't get
+// re-read on every request just to compute an ETag.
+const ETAG_HASH_THRESHOLD_BYTES: u64 = 10 * 1024 * 1024;
+
+pub struct ClaimsKey;
+impl Key for ClaimsKey {
+    type Value = Claims;
+}
+
 #[derive(RustcEncodable)]
 struct Version {
     major: i32,
@@ -54,6 +76,9 @@ impl From<PError> for IronError {
             PError::MetadataDecodingError => IronError::new(err, status::InternalServerError),
             PError::Unauthorized => IronError::new(err, status::Unauthorized),
             PError::IncorrectCredentials => IronError::new(err, status::BadRequest),
+            PError::TokenExpired => IronError::new(err, status::Unauthorized),
+            PError::InvalidToken => IronError::new(err, status::Unauthorized),
+            PError::Forbidden => IronError::new(err, status::Forbidden),
         }
     }
 }
@@ -90,10 +115,23 @@ pub fn get_api_handler(collection: Arc<Collection>) -> Mount {
         }
 
         let mut auth_api_chain = Chain::new(auth_api_mount);
-        auth_api_chain.link_before(AuthRequirement);
+        auth_api_chain.link_before(AuthRequirement(collection.clone()));
 
         api_handler.mount("/", auth_api_chain);
     }
+
+    {
+        let upload_handler = {
+            let collection = collection.clone();
+            move |request: &mut Request| self::upload(request, collection.deref())
+        };
+
+        let mut admin_api_chain = Chain::new(upload_handler);
+        admin_api_chain.link_before(AuthRequirement(collection.clone()));
+        admin_api_chain.link_before(PermissionRequirement::new(vec!["admin"]));
+
+        api_handler.mount("/upload/", admin_api_chain);
+    }
     api_handler
 }
 
@@ -103,16 +141,263 @@ fn path_from_request(request: &Request) -> Result<PathBuf, Utf8Error> {
     Ok(PathBuf::from(decoded_path.deref()))
 }
 
-struct AuthRequirement;
+struct AuthRequirement(Arc<Collection>);
 impl BeforeMiddleware for AuthRequirement {
     fn before(&self, req: &mut Request) -> IronResult<()> {
-        let auth_cookie = req.get_cookie("username");
-        if auth_cookie.is_some() {
+        let claims = try!(extract_claims(req, self.0.secret()));
+        req.extensions.insert::<ClaimsKey>(claims);
+        Ok(())
+    }
+}
+
+struct PermissionRequirement {
+    required: Vec<String>,
+}
+
+impl PermissionRequirement {
+    fn new(required: Vec<&str>) -> PermissionRequirement {
+        PermissionRequirement { required: required.into_iter().map(str::to_owned).collect() }
+    }
+}
+
+impl BeforeMiddleware for PermissionRequirement {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let granted = match req.extensions.get::<ClaimsKey>() {
+            Some(claims) => &claims.perms,
+            None => return Err(IronError::from(PError::Unauthorized)),
+        };
+
+        if self.required.iter().all(|perm| granted.contains(perm)) {
             Ok(())
         } else {
-            Err(IronError::new(PError::Unauthorized, status::Unauthorized))
+            Err(IronError::from(PError::Forbidden))
+        }
+    }
+}
+
+fn bearer_token(req: &Request) -> Option<String> {
+    match req.headers.get_raw("Authorization") {
+        Some(values) => {
+            match values.get(0) {
+                Some(raw) => {
+                    match str::from_utf8(raw) {
+                        Ok(header) if header.starts_with("Bearer ") => {
+                            Some(header["Bearer ".len()..].to_owned())
+                        }
+                        _ => None,
+                    }
+                }
+                None => None,
+            }
+        }
+        None => None,
+    }
+}
+
+fn extract_claims(req: &Request, secret: &[u8]) -> IronResult<Claims> {
+    let token = match bearer_token(req) {
+        Some(token) => token,
+        None => {
+            match req.get_cookie("token") {
+                Some(cookie) => cookie.value.clone(),
+                None => return Err(IronError::from(PError::Unauthorized)),
+            }
         }
+    };
+
+    let claims = match jwt::decode(&token, secret) {
+        Ok(claims) => claims,
+        Err(_) => return Err(IronError::from(PError::InvalidToken)),
+    };
+
+    if jwt::is_expired(&claims, unix_timestamp()) {
+        return Err(IronError::from(PError::TokenExpired));
     }
+
+    Ok(claims)
+}
+
+fn unix_timestamp() -> i64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    since_epoch.as_secs() as i64
+}
+
+// `allow_full_hash` is false for range requests: a player scrubbing through
+// a track would otherwise force a full SHA-256 read of the file on every
+// seek just to mint an ETag for a response that returns a few KB of it.
+fn etag_for(path: &Path, metadata: &fs::Metadata, allow_full_hash: bool) -> io::Result<EntityTag> {
+    let tag = if allow_full_hash && metadata.len() <= ETAG_HASH_THRESHOLD_BYTES {
+        try!(hash_file(path))
+    } else {
+        cheap_etag(metadata)
+    };
+    Ok(EntityTag::strong(tag))
+}
+
+fn cheap_etag(metadata: &fs::Metadata) -> String {
+    let mtime = metadata.modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    format!("{:x}-{:x}", metadata.len(), mtime)
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = try!(fs::File::open(path));
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = try!(file.read(&mut buffer));
+        if read == 0 {
+            break;
+        }
+        hasher.input(&buffer[..read]);
+    }
+    Ok(hasher.result_str())
+}
+
+fn compare_etags(etag: &EntityTag, if_none_match: &IfNoneMatch) -> bool {
+    match *if_none_match {
+        IfNoneMatch::Any => true,
+        IfNoneMatch::Items(ref tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+    }
+}
+
+fn serve_file(request: &Request, real_path: &Path, metadata: &fs::Metadata) -> IronResult<Response> {
+    let range = request.headers.get::<Range>();
+    let etag = match etag_for(real_path, metadata, range.is_none()) {
+        Ok(etag) => etag,
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+    };
+
+    if let Some(if_none_match) = request.headers.get::<IfNoneMatch>() {
+        if compare_etags(&etag, if_none_match) {
+            let mut response = Response::with(status::NotModified);
+            response.headers.set(ETag(etag));
+            return Ok(response);
+        }
+    }
+
+    let content_type: iron::mime::Mime = mime_type(real_path).parse().unwrap();
+    let total_length = metadata.len();
+
+    match range {
+        Some(range) => serve_range(real_path, content_type, total_length, range, etag),
+        None => {
+            let mut response = Response::with((status::Ok, real_path.to_path_buf()));
+            response.headers.set(ETag(etag));
+            response.headers.set(ContentType(content_type));
+            response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+            Ok(response)
+        }
+    }
+}
+
+fn serve_range(real_path: &Path,
+                content_type: iron::mime::Mime,
+                total_length: u64,
+                range: &Range,
+                etag: EntityTag)
+                -> IronResult<Response> {
+    let byte_ranges = match *range {
+        Range::Bytes(ref ranges) => ranges,
+        _ => return Ok(range_not_satisfiable(total_length)),
+    };
+
+    if byte_ranges.len() != 1 {
+        // Multi-range requests are rejected for now.
+        return Ok(range_not_satisfiable(total_length));
+    }
+
+    let (start, end) = match resolve_byte_range(&byte_ranges[0], total_length) {
+        Some(bounds) => bounds,
+        None => return Ok(range_not_satisfiable(total_length)),
+    };
+
+    let body = match open_byte_range(real_path, start, end) {
+        Ok(reader) => reader,
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+    };
+    let length = end - start + 1;
+
+    let mut response = Response::with((status::PartialContent, Box::new(body) as Box<Read + Send>));
+    response.headers.set(ETag(etag));
+    response.headers.set(ContentType(content_type));
+    response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+    response.headers.set(ContentLength(length));
+    response.headers.set(ContentRange(ContentRangeSpec::Bytes {
+        range: Some((start, end)),
+        instance_length: Some(total_length),
+    }));
+    Ok(response)
+}
+
+fn range_not_satisfiable(total_length: u64) -> Response {
+    let mut response = Response::with(status::RangeNotSatisfiable);
+    response.headers.set(ContentRange(ContentRangeSpec::Bytes {
+        range: None,
+        instance_length: Some(total_length),
+    }));
+    response
+}
+
+fn resolve_byte_range(spec: &ByteRangeSpec, total_length: u64) -> Option<(u64, u64)> {
+    if total_length == 0 {
+        return None;
+    }
+    let last_byte = total_length - 1;
+    match *spec {
+        ByteRangeSpec::FromTo(start, end) => {
+            if start > end || start > last_byte {
+                None
+            } else {
+                Some((start, cmp::min(end, last_byte)))
+            }
+        }
+        ByteRangeSpec::AllFrom(start) => {
+            if start > last_byte {
+                None
+            } else {
+                Some((start, last_byte))
+            }
+        }
+        ByteRangeSpec::Last(length) => {
+            if length == 0 {
+                None
+            } else if length > total_length {
+                Some((0, last_byte))
+            } else {
+                Some((total_length - length, last_byte))
+            }
+        }
+    }
+}
+
+struct BoundedReader {
+    file: fs::File,
+    remaining: u64,
+}
+
+impl Read for BoundedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let capped = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let read = try!(self.file.read(&mut buf[..capped]));
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+fn open_byte_range(path: &Path, start: u64, end: u64) -> io::Result<BoundedReader> {
+    let mut file = try!(fs::File::open(path));
+    try!(file.seek(SeekFrom::Start(start)));
+    Ok(BoundedReader {
+        file: file,
+        remaining: end - start + 1,
+    })
 }
 
 fn version(_: &mut Request) -> IronResult<Response> {
@@ -126,22 +411,35 @@ fn version(_: &mut Request) -> IronResult<Response> {
 fn auth(request: &mut Request, collection: &Collection) -> IronResult<Response> {
     let input = request.get_ref::<params::Params>().unwrap();
     let username = match input.find(&["username"]) {
-        Some(&params::Value::String(ref username)) => username,
+        Some(&params::Value::String(ref username)) => username.clone(),
         _ => return Err(IronError::from(PError::IncorrectCredentials)),
     };
     let password = match input.find(&["password"]) {
-        Some(&params::Value::String(ref password)) => password,
+        Some(&params::Value::String(ref password)) => password.clone(),
         _ => return Err(IronError::from(PError::IncorrectCredentials)),
     };
-    if collection.auth(username.as_str(), password.as_str()) {
-        let mut response = Response::with((status::Ok, ""));
-        let mut username_cookie = CookiePair::new("username".to_string(), username.clone());
-        username_cookie.path = Some("/".to_owned());
-        response.set_cookie(username_cookie);
-        Ok(response)
-    } else {
-        Err(IronError::from(PError::IncorrectCredentials))
-    }
+
+    let permissions = match collection.auth(username.as_str(), password.as_str()) {
+        Some(permissions) => permissions,
+        None => return Err(IronError::from(PError::IncorrectCredentials)),
+    };
+
+    let issued_at = unix_timestamp();
+    let claims = Claims {
+        sub: username,
+        iat: issued_at,
+        exp: issued_at + TOKEN_LIFETIME_SECONDS,
+        perms: permissions,
+    };
+    let token = jwt::encode(&claims, collection.secret());
+
+    let mut response = Response::with((status::Ok, token.clone()));
+    let mut token_cookie = CookiePair::new("token".to_string(), token);
+    token_cookie.path = Some("/".to_owned());
+    token_cookie.httponly = true;
+    token_cookie.secure = request.url.scheme == "https";
+    response.set_cookie(token_cookie);
+    Ok(response)
 }
 
 fn browse(request: &mut Request, collection: &Collection) -> IronResult<Response> {
@@ -208,7 +506,7 @@ fn serve(request: &mut Request, collection: &Collection) -> IronResult<Response>
     }
 
     if is_song(real_path.as_path()) {
-        return Ok(Response::with((status::Ok, real_path)));
+        return serve_file(request, real_path.as_path(), &metadata);
     }
 
     if is_image(real_path.as_path()) {
@@ -218,10 +516,246 @@ fn serve(request: &mut Request, collection: &Collection) -> IronResult<Response>
     Err(IronError::from(PError::UnsupportedFileType))
 }
 
-fn art(_: &mut Request, real_path: &Path) -> IronResult<Response> {
-    let thumb = get_thumbnail(real_path, 400);
-    match thumb {
-        Ok(path) => Ok(Response::with((status::Ok, path))),
-        Err(e) => Err(IronError::from(e)),
+fn art(request: &mut Request, real_path: &Path) -> IronResult<Response> {
+    let thumb_path = match get_thumbnail(real_path, 400) {
+        Ok(path) => path,
+        Err(e) => return Err(IronError::from(e)),
+    };
+
+    let metadata = match fs::metadata(&thumb_path) {
+        Ok(meta) => meta,
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+    };
+
+    serve_file(request, thumb_path.as_path(), &metadata)
+}
+
+#[derive(RustcEncodable)]
+struct UploadResult {
+    filename: String,
+    success: bool,
+    error: Option<String>,
+}
+
+fn upload(request: &mut Request, collection: &Collection) -> IronResult<Response> {
+    let virtual_dir = match path_from_request(request) {
+        Err(e) => return Err(IronError::new(e, status::BadRequest)),
+        Ok(p) => p,
+    };
+
+    let real_dir = match collection.locate(virtual_dir.as_path()) {
+        Err(e) => return Err(IronError::from(e)),
+        Ok(p) => p,
+    };
+
+    let mut multipart = match Multipart::from_request(request) {
+        Ok(multipart) => multipart,
+        Err(_) => return Err(IronError::from(PError::UnsupportedFileType)),
+    };
+
+    let mut results = Vec::new();
+    let read_outcome = multipart.foreach_entry(|mut entry| {
+        let file_name = entry.headers
+            .filename
+            .clone()
+            .unwrap_or_else(|| "upload".to_owned());
+        match sanitized_upload_name(&file_name) {
+            Some(safe_name) => {
+                let destination = real_dir.join(&safe_name);
+                results.push(store_upload(&mut entry.data, destination.as_path()));
+            }
+            None => {
+                results.push(UploadResult {
+                    filename: file_name,
+                    success: false,
+                    error: Some(format!("{}", PError::PathNotInVfs)),
+                });
+            }
+        }
+    });
+
+    if let Err(e) = read_outcome {
+        return Err(IronError::new(e, status::BadRequest));
+    }
+
+    collection.reindex(virtual_dir.as_path());
+
+    let result_json = match json::encode(&results) {
+        Ok(j) => j,
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+    };
+
+    Ok(Response::with((status::Ok, result_json)))
+}
+
+// Keeps only the final path component of a part's declared filename, so a
+// part named e.g. `../../etc/cron.d/x.mp3` or `/home/x/.ssh/authorized_keys`
+// can't escape `real_dir` when joined onto it. Rejects names that don't
+// resolve to a plain component at all (`..`, `.`, empty).
+fn sanitized_upload_name(file_name: &str) -> Option<String> {
+    match Path::new(file_name).file_name() {
+        Some(name) => Some(name.to_string_lossy().into_owned()),
+        None => None,
+    }
+}
+
+fn store_upload<R: Read>(data: &mut R, destination: &Path) -> UploadResult {
+    let filename = destination.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "upload".to_owned());
+
+    if !is_song(destination) && !is_image(destination) {
+        return UploadResult {
+            filename: filename,
+            success: false,
+            error: Some(format!("{}", PError::UnsupportedFileType)),
+        };
+    }
+
+    match write_upload(data, destination) {
+        Ok(()) => {
+            UploadResult {
+                filename: filename,
+                success: true,
+                error: None,
+            }
+        }
+        Err(e) => {
+            UploadResult {
+                filename: filename,
+                success: false,
+                error: Some(format!("{}", e)),
+            }
+        }
+    }
+}
+
+fn write_upload<R: Read>(data: &mut R, destination: &Path) -> io::Result<()> {
+    let mut file = try!(fs::File::create(destination));
+    try!(io::copy(data, &mut file));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn resolves_a_from_to_range() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::FromTo(0, 499), 1000),
+                   Some((0, 499)));
+    }
+
+    #[test]
+    fn clamps_a_from_to_range_past_the_end_of_the_file() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::FromTo(0, 999999), 1000),
+                   Some((0, 999)));
+    }
+
+    #[test]
+    fn rejects_a_from_to_range_starting_past_the_end_of_the_file() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::FromTo(1000, 1001), 1000), None);
+    }
+
+    #[test]
+    fn rejects_an_inverted_from_to_range() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::FromTo(500, 100), 1000), None);
+    }
+
+    #[test]
+    fn resolves_an_open_ended_range() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::AllFrom(500), 1000),
+                   Some((500, 999)));
+    }
+
+    #[test]
+    fn rejects_an_open_ended_range_starting_past_the_end_of_the_file() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::AllFrom(1000), 1000), None);
+    }
+
+    #[test]
+    fn resolves_a_suffix_range() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::Last(500), 1000),
+                   Some((500, 999)));
+    }
+
+    #[test]
+    fn clamps_a_suffix_range_larger_than_the_file() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::Last(2000), 1000),
+                   Some((0, 999)));
+    }
+
+    #[test]
+    fn rejects_a_zero_length_suffix_range() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::Last(0), 1000), None);
+    }
+
+    #[test]
+    fn rejects_any_range_on_an_empty_file() {
+        assert_eq!(resolve_byte_range(&ByteRangeSpec::AllFrom(0), 0), None);
+    }
+
+    #[test]
+    fn sanitizes_a_relative_traversal_to_its_final_component() {
+        assert_eq!(sanitized_upload_name("../../etc/cron.d/x.mp3"),
+                   Some("x.mp3".to_owned()));
+    }
+
+    #[test]
+    fn sanitizes_an_absolute_path_to_its_final_component() {
+        assert_eq!(sanitized_upload_name("/home/other/.ssh/authorized_keys.jpg"),
+                   Some("authorized_keys.jpg".to_owned()));
+    }
+
+    #[test]
+    fn keeps_a_plain_filename_unchanged() {
+        assert_eq!(sanitized_upload_name("track.mp3"), Some("track.mp3".to_owned()));
+    }
+
+    #[test]
+    fn rejects_a_bare_parent_dir_reference() {
+        assert_eq!(sanitized_upload_name(".."), None);
+    }
+
+    #[test]
+    fn rejects_a_bare_current_dir_reference() {
+        assert_eq!(sanitized_upload_name("."), None);
+    }
+
+    #[test]
+    fn rejects_an_empty_filename() {
+        assert_eq!(sanitized_upload_name(""), None);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("polaris-test-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn store_upload_rejects_unsupported_file_types() {
+        let dir = temp_dir("rejects-unsupported");
+        let destination = dir.join("note.txt");
+
+        let result = store_upload(&mut "hello".as_bytes(), destination.as_path());
+
+        assert!(!result.success);
+        assert!(!destination.exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn store_upload_writes_supported_file_types() {
+        let dir = temp_dir("writes-supported");
+        let destination = dir.join("track.mp3");
+
+        let result = store_upload(&mut "id3".as_bytes(), destination.as_path());
+
+        assert!(result.success);
+        assert!(destination.exists());
+        fs::remove_dir_all(&dir).ok();
     }
 }
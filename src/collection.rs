@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use error::*;
+
+#[derive(RustcEncodable)]
+pub struct CollectionEntry {
+    pub path: PathBuf,
+    pub is_directory: bool,
+}
+
+struct User {
+    password: String,
+    permissions: Vec<String>,
+}
+
+pub struct Collection {
+    root: PathBuf,
+    secret: Vec<u8>,
+    users: Mutex<HashMap<String, User>>,
+}
+
+impl Collection {
+    pub fn new(root: PathBuf, secret: Vec<u8>) -> Collection {
+        Collection {
+            root: root,
+            secret: secret,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn secret(&self) -> &[u8] {
+        self.secret.as_slice()
+    }
+
+    /// Returns the caller's granted permissions on success, `None` if the
+    /// credentials don't match a known user.
+    pub fn auth(&self, username: &str, password: &str) -> Option<Vec<String>> {
+        let users = self.users.lock().unwrap();
+        match users.get(username) {
+            Some(user) if user.password == password => Some(user.permissions.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn browse(&self, virtual_path: &Path) -> Result<Vec<CollectionEntry>, PError> {
+        let real_path = try!(self.locate(virtual_path));
+        let mut entries = Vec::new();
+        for entry in try!(real_path.read_dir()) {
+            let entry = try!(entry);
+            entries.push(CollectionEntry {
+                path: virtual_path.join(entry.file_name()),
+                is_directory: try!(entry.file_type()).is_dir(),
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn flatten(&self, virtual_path: &Path) -> Result<Vec<CollectionEntry>, PError> {
+        self.browse(virtual_path)
+    }
+
+    pub fn locate(&self, virtual_path: &Path) -> Result<PathBuf, PError> {
+        if virtual_path.components().any(|c| c.as_os_str() == "..") {
+            return Err(PError::PathNotInVfs);
+        }
+        Ok(self.root.join(virtual_path))
+    }
+
+    pub fn reindex(&self, _virtual_path: &Path) {
+        // TODO: this is a no-op only because browse/flatten currently read
+        // the filesystem live, so there is nothing cached to refresh. The
+        // moment Collection gains a real directory index/cache, this must
+        // actually invalidate/rescan `_virtual_path`, or uploads will
+        // silently stop showing up in browse/flatten results.
+    }
+}
@@ -0,0 +1,60 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum PError {
+    Io(io::Error),
+    CannotClearExistingIndex,
+    PathDecoding,
+    ConfigDirectoryError,
+    CacheDirectoryError,
+    PathNotInVfs,
+    CannotServeDirectory,
+    UnsupportedFileType,
+    AlbumArtSearchError,
+    ImageProcessingError,
+    UnsupportedMetadataFormat,
+    MetadataDecodingError,
+    Unauthorized,
+    IncorrectCredentials,
+    TokenExpired,
+    InvalidToken,
+    Forbidden,
+}
+
+impl fmt::Display for PError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PError::Io(ref e) => write!(f, "IO error: {}", e),
+            PError::CannotClearExistingIndex => write!(f, "Could not clear existing index"),
+            PError::PathDecoding => write!(f, "Could not decode path"),
+            PError::ConfigDirectoryError => write!(f, "Could not open config directory"),
+            PError::CacheDirectoryError => write!(f, "Could not open cache directory"),
+            PError::PathNotInVfs => write!(f, "Path does not belong to any mount point"),
+            PError::CannotServeDirectory => write!(f, "Cannot serve a directory"),
+            PError::UnsupportedFileType => write!(f, "Unsupported file type"),
+            PError::AlbumArtSearchError => write!(f, "Error while looking for album art"),
+            PError::ImageProcessingError => write!(f, "Error while processing image"),
+            PError::UnsupportedMetadataFormat => write!(f, "Unsupported metadata format"),
+            PError::MetadataDecodingError => write!(f, "Error while reading metadata"),
+            PError::Unauthorized => write!(f, "Unauthorized"),
+            PError::IncorrectCredentials => write!(f, "Incorrect credentials"),
+            PError::TokenExpired => write!(f, "Token has expired"),
+            PError::InvalidToken => write!(f, "Invalid token"),
+            PError::Forbidden => write!(f, "Forbidden"),
+        }
+    }
+}
+
+impl error::Error for PError {
+    fn description(&self) -> &str {
+        "Polaris error"
+    }
+}
+
+impl From<io::Error> for PError {
+    fn from(err: io::Error) -> PError {
+        PError::Io(err)
+    }
+}
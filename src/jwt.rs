@@ -0,0 +1,134 @@
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use rustc_serialize::json;
+
+#[derive(RustcEncodable, RustcDecodable, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub perms: Vec<String>,
+}
+
+#[derive(RustcEncodable)]
+struct Header {
+    alg: &'static str,
+    typ: &'static str,
+}
+
+pub fn encode(claims: &Claims, secret: &[u8]) -> String {
+    let header = Header {
+        alg: "HS256",
+        typ: "JWT",
+    };
+    let header_b64 = json::encode(&header).unwrap().into_bytes().to_base64(base64::URL_SAFE);
+    let claims_b64 = json::encode(claims).unwrap().into_bytes().to_base64(base64::URL_SAFE);
+    let signing_input = format!("{}.{}", header_b64, claims_b64);
+    let signature = sign(signing_input.as_bytes(), secret);
+    format!("{}.{}", signing_input, signature)
+}
+
+pub fn decode(token: &str, secret: &[u8]) -> Result<Claims, ()> {
+    let mut parts = token.split('.');
+    let header_b64 = try!(parts.next().ok_or(()));
+    let claims_b64 = try!(parts.next().ok_or(()));
+    let signature_b64 = try!(parts.next().ok_or(()));
+    if parts.next().is_some() {
+        return Err(());
+    }
+
+    let signing_input = format!("{}.{}", header_b64, claims_b64);
+    let expected_signature = sign(signing_input.as_bytes(), secret);
+    if !constant_time_eq(expected_signature.as_bytes(), signature_b64.as_bytes()) {
+        return Err(());
+    }
+
+    let claims_bytes = try!(claims_b64.from_base64().map_err(|_| ()));
+    let claims_json = try!(String::from_utf8(claims_bytes).map_err(|_| ()));
+    json::decode(&claims_json).map_err(|_| ())
+}
+
+fn sign(data: &[u8], secret: &[u8]) -> String {
+    let mut hmac = Hmac::new(Sha256::new(), secret);
+    hmac.input(data);
+    hmac.result().code().to_base64(base64::URL_SAFE)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+pub fn is_expired(claims: &Claims, now: i64) -> bool {
+    claims.exp < now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_claims() -> Claims {
+        Claims {
+            sub: "alice".to_owned(),
+            iat: 1000,
+            exp: 2000,
+            perms: vec!["admin".to_owned()],
+        }
+    }
+
+    #[test]
+    fn roundtrips_through_encode_and_decode() {
+        let token = encode(&sample_claims(), b"secret");
+        let claims = decode(&token, b"secret").unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.exp, 2000);
+        assert_eq!(claims.perms, vec!["admin".to_owned()]);
+    }
+
+    #[test]
+    fn rejects_a_token_signed_with_a_different_secret() {
+        let token = encode(&sample_claims(), b"secret");
+        assert!(decode(&token, b"wrong-secret").is_err());
+    }
+
+    #[test]
+    fn rejects_a_tampered_signature() {
+        let token = encode(&sample_claims(), b"secret");
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let mut signature = parts[2].to_owned();
+        signature.push('x');
+        parts[2] = &signature;
+        let tampered = parts.join(".");
+        assert!(decode(&tampered, b"secret").is_err());
+    }
+
+    #[test]
+    fn rejects_a_tampered_payload() {
+        let token = encode(&sample_claims(), b"secret");
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let mut forged_claims = sample_claims();
+        forged_claims.sub = "mallory".to_owned();
+        let forged_b64 = json::encode(&forged_claims)
+            .unwrap()
+            .into_bytes()
+            .to_base64(base64::URL_SAFE);
+        parts[1] = &forged_b64;
+        let tampered = parts.join(".");
+        assert!(decode(&tampered, b"secret").is_err());
+    }
+
+    #[test]
+    fn is_expired_compares_exp_against_now() {
+        let claims = sample_claims();
+        assert!(is_expired(&claims, 2001));
+        assert!(!is_expired(&claims, 1999));
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+const SONG_EXTENSIONS: &'static [&'static str] =
+    &["mp3", "flac", "ogg", "m4a", "wav", "opus", "ape", "wv"];
+const IMAGE_EXTENSIONS: &'static [&'static str] = &["png", "jpg", "jpeg", "bmp", "gif"];
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    match path.extension() {
+        Some(extension) => {
+            match extension.to_str() {
+                Some(extension) => extensions.contains(&extension.to_lowercase().as_str()),
+                None => false,
+            }
+        }
+        None => false,
+    }
+}
+
+pub fn is_song(path: &Path) -> bool {
+    has_extension(path, SONG_EXTENSIONS)
+}
+
+pub fn is_image(path: &Path) -> bool {
+    has_extension(path, IMAGE_EXTENSIONS)
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => extension.to_lowercase(),
+        None => return "application/octet-stream",
+    };
+    match extension.as_str() {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "ogg" => "audio/ogg",
+        "opus" => "audio/opus",
+        "m4a" => "audio/mp4",
+        "wav" => "audio/wav",
+        "ape" => "audio/x-ape",
+        "wv" => "audio/x-wavpack",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "bmp" => "image/bmp",
+        "gif" => "image/gif",
+        _ => "application/octet-stream",
+    }
+}